@@ -1,23 +1,32 @@
 extern crate globset;
 
 use globset::{GlobBuilder, GlobSet, GlobSetBuilder};
+use std::collections::HashSet;
+use std::env;
 use std::fs;
 use std::io;
 use std::io::Read;
 use std::path::{Path, PathBuf};
 
-pub fn load(path: &Path) -> Option<GitignoreFile> {
+/// Walks up from `path` to the enclosing `.git` directory, collecting every
+/// ignore source git itself consults into a `GitignoreStack`: per-directory
+/// `.gitignore` and `.ignore` files, `.git/info/exclude`, and the
+/// `core.excludesFile` (or its `$XDG_CONFIG_HOME/git/ignore` default).
+///
+/// Unlike a single `GitignoreFile`, the stack keeps track of all of them, so
+/// that nested ignore files are honored with the most specific one taking
+/// precedence, just like git itself does.
+pub fn load(path: &Path) -> GitignoreStack {
+    let mut stack = GitignoreStack::new();
     let mut p = path.to_owned();
 
     loop {
-        let gitignore_path = p.join(".gitignore");
-        if gitignore_path.exists() {
-            return GitignoreFile::new(&gitignore_path).ok();
-        }
+        stack.add_root(&p);
 
         // Stop if we see a .git directory
         if let Ok(metadata) = p.join(".git").metadata() {
             if metadata.is_dir() {
+                stack.add_global_sources(&p);
                 break;
             }
         }
@@ -29,16 +38,246 @@ pub fn load(path: &Path) -> Option<GitignoreFile> {
         p.pop();
     }
 
+    stack
+}
+
+fn read_to_string(path: &Path) -> Option<String> {
+    let mut file = match fs::File::open(path) {
+        Ok(file) => file,
+        Err(_) => return None,
+    };
+
+    let mut contents = String::new();
+    if file.read_to_string(&mut contents).is_err() {
+        return None;
+    }
+
+    Some(contents)
+}
+
+fn expand_tilde(path: &str) -> PathBuf {
+    if let Ok(home) = env::var("HOME") {
+        if path == "~" {
+            return PathBuf::from(home);
+        }
+
+        if let Some(rest) = path.strip_prefix("~/") {
+            return PathBuf::from(home).join(rest);
+        }
+    }
+
+    PathBuf::from(path)
+}
+
+/// Reads `core.excludesFile` out of `git_dir/config`, if set.
+fn core_excludes_file(git_dir: &Path) -> Option<PathBuf> {
+    let contents = match read_to_string(&git_dir.join("config")) {
+        Some(contents) => contents,
+        None => return None,
+    };
+
+    let mut in_core_section = false;
+    for line in contents.lines() {
+        let line = line.trim();
+
+        if line.starts_with('[') {
+            let section = line.trim_start_matches('[').trim_end_matches(']');
+            in_core_section = section.eq_ignore_ascii_case("core");
+            continue;
+        }
+
+        if !in_core_section {
+            continue;
+        }
+
+        if let Some(eq) = line.find('=') {
+            let key = line[..eq].trim();
+            if key.eq_ignore_ascii_case("excludesfile") {
+                return Some(expand_tilde(line[eq + 1..].trim()));
+            }
+        }
+    }
+
     None
+}
+
+/// The default global ignore file git falls back to when `core.excludesFile`
+/// is unset: `$XDG_CONFIG_HOME/git/ignore`, defaulting to `~/.config`.
+fn default_global_ignore_path() -> Option<PathBuf> {
+    let config_home = match env::var("XDG_CONFIG_HOME") {
+        Ok(dir) => PathBuf::from(dir),
+        Err(_) => match env::var("HOME") {
+            Ok(home) => PathBuf::from(home).join(".config"),
+            Err(_) => return None,
+        },
+    };
+
+    Some(config_home.join("git").join("ignore"))
+}
+
+/// A collection of `GitignoreFile`s gathered from a directory and its
+/// ancestors, ordered so that the most specific (deepest-rooted) file is
+/// always consulted first.
+pub struct GitignoreStack {
+    files: Vec<GitignoreFile>,
+    loaded: HashSet<PathBuf>,
+}
+
+impl GitignoreStack {
+    pub fn new() -> GitignoreStack {
+        GitignoreStack { files: vec![], loaded: HashSet::new() }
+    }
+
+    /// Looks for a `.gitignore` and watchexec-specific `.ignore` directly
+    /// inside `dir` and, if found, adds them to the stack rooted at `dir`.
+    /// Safe to call repeatedly as new directories are discovered during a
+    /// walk.
+    pub fn add_root(&mut self, dir: &Path) {
+        self.add_file(&dir.join(".ignore"), dir, PRIORITY_WATCHEXEC_IGNORE);
+        self.add_file(&dir.join(".gitignore"), dir, PRIORITY_GITIGNORE);
+    }
+
+    /// Adds `.git/info/exclude` and the `core.excludesFile` (or its
+    /// `$XDG_CONFIG_HOME/git/ignore` default), both rooted at the repo's top
+    /// level, since they apply repo-wide rather than to one directory.
+    pub fn add_global_sources(&mut self, repo_root: &Path) {
+        let git_dir = repo_root.join(".git");
+
+        self.add_file(&git_dir.join("info").join("exclude"), repo_root, PRIORITY_INFO_EXCLUDE);
+
+        let global_ignore = core_excludes_file(&git_dir).or_else(default_global_ignore_path);
+        if let Some(global_ignore) = global_ignore {
+            self.add_file(&global_ignore, repo_root, PRIORITY_CORE_EXCLUDES);
+        }
+    }
+
+    /// Adds the ignore file at `path`, unless this exact path has already
+    /// been tried. A path is recorded as tried even when no file exists there
+    /// (or it fails to parse), so a file created there later is not picked
+    /// up by another call. Callers such as `add_root` are expected to be invoked
+    /// repeatedly as a filesystem walk revisits directories, so without this
+    /// guard the same root would accumulate duplicate entries on every call.
+    fn add_file(&mut self, path: &Path, root: &Path, priority: i32) {
+        if !self.loaded.insert(path.to_owned()) {
+            return;
+        }
+
+        if !path.exists() {
+            return;
+        }
+
+        if let Ok(file) = GitignoreFile::at_root(path, root, priority) {
+            self.push(file);
+        }
+    }
+
+    /// Adds a `GitignoreFile`, keeping the stack ordered deepest-root-first,
+    /// and highest-priority-first among files sharing a root.
+    pub fn push(&mut self, file: GitignoreFile) {
+        let depth = file.root.components().count();
+        let priority = file.priority;
+
+        let index = self.files
+            .iter()
+            .position(|f| {
+                let existing_depth = f.root.components().count();
+                existing_depth < depth || (existing_depth == depth && f.priority < priority)
+            })
+            .unwrap_or_else(|| self.files.len());
+
+        self.files.insert(index, file);
+    }
+
+    pub fn is_excluded(&self, path: &Path) -> bool {
+        self.is_excluded_with_metadata(path, None)
+    }
+
+    pub fn is_excluded_with_metadata(&self, path: &Path, is_dir: Option<bool>) -> bool {
+        self.matches(path, is_dir).unwrap_or(false)
+    }
+
+    /// Mirrors `GitignoreFile::matches`'s ancestor walk, but across every
+    /// applicable file in the stack rather than delegating the whole
+    /// decision to whichever single file answers first: git does not let a
+    /// whitelist in a deeper, more specific file re-include a path whose
+    /// parent directory was excluded by a shallower one.
+    fn matches(&self, path: &Path, is_dir: Option<bool>) -> Option<bool> {
+        let shallowest_root = self.files
+            .iter()
+            .filter(|f| path.starts_with(&f.root))
+            .map(|f| f.root.clone())
+            .min_by_key(|root| root.components().count());
+
+        let shallowest_root = match shallowest_root {
+            Some(root) => root,
+            None => return None,
+        };
+
+        let relative = match path.strip_prefix(&shallowest_root) {
+            Ok(relative) => relative,
+            Err(_) => return None,
+        };
+
+        let components: Vec<_> = relative.components().collect();
+        if components.is_empty() {
+            return None;
+        }
+
+        let last = components.len() - 1;
+        let mut prefix = shallowest_root;
+        let mut result = None;
+
+        for (i, component) in components.iter().enumerate() {
+            prefix.push(component);
+
+            // Every component but the last is necessarily a directory.
+            let component_is_dir = i != last || GitignoreFile::path_is_dir(path, is_dir);
+
+            if let Some(excluded) = self.matches_prefix(&prefix, component_is_dir) {
+                result = Some(excluded);
+
+                if excluded && i != last {
+                    return Some(true);
+                }
+            }
+        }
+
+        result
+    }
 
+    /// Asks every file whose root is an ancestor of (or equal to) `prefix`,
+    /// most-specific (and then highest-priority) first, and returns the
+    /// first opinion any of them has.
+    fn matches_prefix(&self, prefix: &Path, is_dir: bool) -> Option<bool> {
+        for file in &self.files {
+            if !prefix.starts_with(&file.root) {
+                continue;
+            }
+
+            if let Some(excluded) = file.matches_at(prefix, is_dir) {
+                return Some(excluded);
+            }
+        }
+
+        None
+    }
 }
 
 pub struct GitignoreFile {
     set: GlobSet,
     patterns: Vec<Pattern>,
     root: PathBuf,
+    priority: i32,
 }
 
+// Higher priority sources are consulted before lower priority ones when
+// several share the same root, mirroring git's own precedence: patterns
+// local to the repo override the global ones it falls back to.
+const PRIORITY_WATCHEXEC_IGNORE: i32 = 30;
+const PRIORITY_GITIGNORE: i32 = 20;
+const PRIORITY_INFO_EXCLUDE: i32 = 10;
+const PRIORITY_CORE_EXCLUDES: i32 = 0;
+
 #[derive(Debug)]
 pub enum Error {
     GlobSet(globset::Error),
@@ -49,6 +288,7 @@ struct Pattern {
     pattern: String,
     pattern_type: PatternType,
     anchored: bool,
+    directory_only: bool,
 }
 
 enum PatternType {
@@ -68,6 +308,22 @@ impl GitignoreFile {
         GitignoreFile::from_strings(lines, root)
     }
 
+    /// Reads `path` like `new`, but roots the resulting file at `root` and
+    /// tags it with `priority` rather than defaulting both. Used for sources
+    /// that live outside of the directory they apply to, such as
+    /// `.git/info/exclude` or `core.excludesFile`.
+    fn at_root(path: &Path, root: &Path, priority: i32) -> Result<GitignoreFile, Error> {
+        let mut file = try!(fs::File::open(path));
+        let mut contents = String::new();
+        try!(file.read_to_string(&mut contents));
+
+        let lines = contents.lines().collect();
+        let mut file = try!(GitignoreFile::from_strings(lines, root));
+        file.priority = priority;
+
+        Ok(file)
+    }
+
     pub fn from_strings(strs: Vec<&str>, root: &Path) -> Result<GitignoreFile, Error> {
         let mut builder = GlobSetBuilder::new();
         let mut patterns = vec![];
@@ -95,27 +351,99 @@ impl GitignoreFile {
             set: try!(builder.build()),
             patterns: patterns,
             root: root.to_owned(),
+            priority: PRIORITY_GITIGNORE,
         })
 
     }
 
     pub fn is_excluded(&self, path: &Path) -> bool {
-        let stripped = path.strip_prefix(&self.root);
-        if !stripped.is_ok() {
-            return false;
+        self.is_excluded_with_metadata(path, None)
+    }
+
+    /// Like `is_excluded`, but lets the caller pass in whether `path` is a
+    /// directory instead of relying on `fs::metadata`. Useful when the caller
+    /// already knows this, e.g. from a filesystem event, and `path` may no
+    /// longer exist on disk (as happens on deletion).
+    pub fn is_excluded_with_metadata(&self, path: &Path, is_dir: Option<bool>) -> bool {
+        self.matches(path, is_dir).unwrap_or(false)
+    }
+
+    /// Returns `Some(excluded)` if this file has an opinion on `path`, or
+    /// `None` if none of its patterns match. `GitignoreStack` does not call
+    /// this; it walks the ancestors itself and asks each file through
+    /// `matches_at`.
+    ///
+    /// Evaluates every ancestor directory of `path` in turn, not just the
+    /// full path: git does not allow a `!whitelist` pattern to re-include a
+    /// file whose parent directory is itself excluded, so if an ancestor is
+    /// matched by an ignore pattern, the leaf stays excluded regardless of
+    /// what a deeper pattern says.
+    fn matches(&self, path: &Path, is_dir: Option<bool>) -> Option<bool> {
+        let stripped = match path.strip_prefix(&self.root) {
+            Ok(stripped) => stripped,
+            Err(_) => return None,
+        };
+
+        let components: Vec<_> = stripped.components().collect();
+        if components.is_empty() {
+            return None;
         }
 
-        let matches = self.set.matches(stripped.unwrap());
+        let last = components.len() - 1;
+        let mut prefix = PathBuf::new();
+        let mut result = None;
+
+        for (i, component) in components.iter().enumerate() {
+            prefix.push(component);
+
+            // Every component but the last is necessarily a directory.
+            let component_is_dir = i == last && Self::path_is_dir(path, is_dir) || i != last;
+
+            if let Some(excluded) = self.matches_component(&prefix, component_is_dir) {
+                result = Some(excluded);
+
+                if excluded && i != last {
+                    return Some(true);
+                }
+            }
+        }
+
+        result
+    }
+
+    /// Like `matches_component`, but takes an absolute path and strips this
+    /// file's own root off of it first. Used by `GitignoreStack` to ask a
+    /// single file's opinion on one level of a cross-file ancestor walk.
+    fn matches_at(&self, absolute_path: &Path, is_dir: bool) -> Option<bool> {
+        let relative = match absolute_path.strip_prefix(&self.root) {
+            Ok(relative) => relative,
+            Err(_) => return None,
+        };
+
+        self.matches_component(relative, is_dir)
+    }
+
+    fn matches_component(&self, candidate: &Path, is_dir: bool) -> Option<bool> {
+        let matches = self.set.matches(candidate);
 
         for &i in matches.iter().rev() {
             let pattern = &self.patterns[i];
-            return match pattern.pattern_type {
+
+            if pattern.directory_only && !is_dir {
+                continue;
+            }
+
+            return Some(match pattern.pattern_type {
                 PatternType::Whitelist  => false,
                 PatternType::Ignore     => true,
-            }
+            })
         }
 
-        false
+        None
+    }
+
+    fn path_is_dir(path: &Path, is_dir: Option<bool>) -> bool {
+        is_dir.unwrap_or_else(|| fs::metadata(path).map(|m| m.is_dir()).unwrap_or(false))
     }
 
     fn parse(contents: Vec<&str>) -> Vec<Pattern> {
@@ -145,7 +473,8 @@ impl Pattern {
             false
         };
 
-        if normalized.ends_with('/') {
+        let directory_only = normalized.ends_with('/');
+        if directory_only {
             normalized.pop();
         }
 
@@ -157,6 +486,7 @@ impl Pattern {
             pattern: normalized,
             pattern_type: pattern_type,
             anchored: anchored,
+            directory_only: directory_only,
         }
     }
 }
@@ -285,11 +615,157 @@ mod tests {
 
     #[test]
     fn test_handles_whitelisting() {
-        let patterns = vec!["target", "!target/foo.txt"];
+        let patterns = vec!["*.log", "!important.log"];
+        let file = GitignoreFile::from_strings(patterns, &base_dir()).unwrap();
+
+        assert!(!file.is_excluded(&base_dir().join("important.log")));
+        assert!(file.is_excluded(&base_dir().join("debug.log")));
+    }
+
+    #[test]
+    fn test_whitelist_cannot_reinclude_file_under_excluded_dir() {
+        let patterns = vec!["build/", "!build/keep.txt"];
+        let file = GitignoreFile::from_strings(patterns, &base_dir()).unwrap();
+
+        assert!(file.is_excluded_with_metadata(&base_dir().join("build"), Some(true)));
+        assert!(file.is_excluded_with_metadata(&base_dir().join("build").join("keep.txt"), Some(false)));
+    }
+
+    #[test]
+    fn test_whitelist_can_reinclude_the_excluded_dir_itself() {
+        let patterns = vec!["build/", "!build/"];
         let file = GitignoreFile::from_strings(patterns, &base_dir()).unwrap();
 
-        assert!(!file.is_excluded(&base_dir().join("target").join("foo.txt")));
-        assert!(file.is_excluded(&base_dir().join("target").join("blah.txt")));
+        assert!(!file.is_excluded_with_metadata(&base_dir().join("build"), Some(true)));
+    }
+
+    #[test]
+    fn test_stack_whitelist_in_deeper_file_cannot_reinclude_ancestor_excluded_by_shallower_file() {
+        use super::GitignoreStack;
+
+        let root = base_dir();
+        let build = root.join("build");
+
+        let mut stack = GitignoreStack::new();
+        stack.push(GitignoreFile::from_strings(vec!["build/"], &root).unwrap());
+        stack.push(GitignoreFile::from_strings(vec!["!keep.txt"], &build).unwrap());
+
+        assert!(stack.is_excluded_with_metadata(&build.join("keep.txt"), Some(false)));
+    }
+
+    #[test]
+    fn test_real_nested_gitignore_files_respect_ancestor_exclusion() {
+        use super::GitignoreStack;
+        use std::fs;
+
+        let root = std::env::temp_dir().join("watchexec_test_nested_ancestor_exclusion");
+        let build = root.join("build");
+        fs::create_dir_all(&build).unwrap();
+        fs::write(root.join(".gitignore"), "build/\n").unwrap();
+        fs::write(build.join(".gitignore"), "!keep.txt\n").unwrap();
+
+        let mut stack = GitignoreStack::new();
+        stack.add_root(&root);
+        stack.add_root(&build);
+
+        assert!(stack.is_excluded_with_metadata(&build.join("keep.txt"), Some(false)));
+
+        fs::remove_dir_all(&root).ok();
+    }
+
+    #[test]
+    fn test_directory_only_pattern_matches_directory() {
+        let file = build_gitignore("logs/");
+
+        assert!(file.is_excluded_with_metadata(&base_dir().join("logs"), Some(true)));
+        assert!(file.is_excluded_with_metadata(&base_dir().join("logs").join("x"), Some(true)));
+    }
+
+    #[test]
+    fn test_directory_only_pattern_does_not_match_file() {
+        let file = build_gitignore("logs/");
+
+        assert!(!file.is_excluded_with_metadata(&base_dir().join("logs"), Some(false)));
+    }
+
+    #[test]
+    fn test_directory_only_pattern_excludes_file_nested_in_ignored_dir() {
+        let file = build_gitignore("logs/");
+
+        // The caller correctly reports this as a file (it's a descendant of
+        // `logs/`, not `logs/` itself), so the directory_only gate must not
+        // be checked against the leaf alone: `logs/` still excludes it.
+        assert!(file.is_excluded_with_metadata(&base_dir().join("logs").join("app.log"), Some(false)));
+    }
+
+    #[test]
+    fn test_stack_prefers_most_specific_file() {
+        use super::GitignoreStack;
+
+        let root = base_dir();
+        let nested = root.join("a").join("b");
+
+        let mut stack = GitignoreStack::new();
+        stack.push(GitignoreFile::from_strings(vec!["*.txt"], &root).unwrap());
+        stack.push(GitignoreFile::from_strings(vec!["!keep.txt"], &nested).unwrap());
+
+        assert!(!stack.is_excluded(&nested.join("keep.txt")));
+        assert!(stack.is_excluded(&nested.join("other.txt")));
+        assert!(stack.is_excluded(&root.join("top.txt")));
+    }
+
+    #[test]
+    fn test_add_root_does_not_duplicate_entries_on_repeated_calls() {
+        use super::GitignoreStack;
+        use std::fs;
+
+        let dir = std::env::temp_dir().join("watchexec_test_add_root_idempotent");
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join(".gitignore"), "*.log\n").unwrap();
+
+        let mut stack = GitignoreStack::new();
+        stack.add_root(&dir);
+        stack.add_root(&dir);
+        stack.add_root(&dir);
+
+        assert_eq!(stack.files.len(), 1);
+
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_local_source_overrides_global_source_at_same_root() {
+        use super::{GitignoreStack, PRIORITY_CORE_EXCLUDES, PRIORITY_GITIGNORE};
+
+        let root = base_dir();
+
+        let mut global = GitignoreFile::from_strings(vec!["!*.log"], &root).unwrap();
+        global.priority = PRIORITY_CORE_EXCLUDES;
+
+        let mut local = GitignoreFile::from_strings(vec!["*.log"], &root).unwrap();
+        local.priority = PRIORITY_GITIGNORE;
+
+        let mut stack = GitignoreStack::new();
+        stack.push(global);
+        stack.push(local);
+
+        assert!(stack.is_excluded(&root.join("debug.log")));
+    }
+
+    #[test]
+    fn test_stack_falls_back_to_ancestor_when_no_match() {
+        use super::GitignoreStack;
+
+        let root = base_dir();
+        let nested = root.join("a");
+
+        let mut stack = GitignoreStack::new();
+        stack.push(GitignoreFile::from_strings(vec!["*.txt"], &root).unwrap());
+        stack.push(GitignoreFile::from_strings(vec!["*.log"], &nested).unwrap());
+
+        assert!(stack.is_excluded(&nested.join("file.txt")));
+        assert!(stack.is_excluded(&nested.join("file.log")));
+        assert!(!stack.is_excluded(&nested.join("file.rs")));
     }
 }
 
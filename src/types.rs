@@ -0,0 +1,183 @@
+extern crate globset;
+
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use std::collections::HashMap;
+use std::path::Path;
+
+/// The result of matching a path against a `Types` selection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Match {
+    /// The path matches a selected type.
+    Included,
+    /// The path matches a negated type.
+    Ignored,
+    /// The path matches neither a selected nor a negated type (it may still
+    /// match a known type that is not part of the selection).
+    Unmatched,
+}
+
+#[derive(Debug)]
+pub enum Error {
+    GlobSet(globset::Error),
+    UnknownType(String),
+}
+
+impl From<globset::Error> for Error {
+    fn from(error: globset::Error) -> Error {
+        Error::GlobSet(error)
+    }
+}
+
+fn default_types() -> Vec<(&'static str, &'static [&'static str])> {
+    vec![
+        ("rust", &["*.rs"]),
+        ("c", &["*.c", "*.h"]),
+        ("cpp", &["*.cpp", "*.cc", "*.cxx", "*.hpp", "*.hh"]),
+        ("md", &["*.md", "*.markdown"]),
+        ("py", &["*.py"]),
+        ("js", &["*.js", "*.jsx"]),
+        ("toml", &["*.toml"]),
+    ]
+}
+
+/// Builds a `Types` matcher out of the default type definitions, any
+/// user-defined ones, and a `select`/`negate` list. Like
+/// `GitignoreFile::from_strings`, it compiles globs into `GlobSet`s, but
+/// into two of them: one for the selected types and one for the negated
+/// types, not a single combined set.
+pub struct TypesBuilder {
+    definitions: HashMap<String, Vec<String>>,
+    selected: Vec<String>,
+    negated: Vec<String>,
+}
+
+impl TypesBuilder {
+    pub fn new() -> TypesBuilder {
+        let mut definitions = HashMap::new();
+        for (name, globs) in default_types() {
+            definitions.insert(name.to_string(), globs.iter().map(|g| g.to_string()).collect());
+        }
+
+        TypesBuilder {
+            definitions: definitions,
+            selected: vec![],
+            negated: vec![],
+        }
+    }
+
+    /// Defines (or overrides) a named type as a list of glob patterns.
+    pub fn add_def(&mut self, name: &str, globs: Vec<&str>) -> &mut TypesBuilder {
+        self.definitions.insert(name.to_string(), globs.into_iter().map(|g| g.to_string()).collect());
+        self
+    }
+
+    /// Includes files matching the named type.
+    pub fn select(&mut self, name: &str) -> &mut TypesBuilder {
+        self.selected.push(name.to_string());
+        self
+    }
+
+    /// Excludes files matching the named type, overriding `select`.
+    pub fn negate(&mut self, name: &str) -> &mut TypesBuilder {
+        self.negated.push(name.to_string());
+        self
+    }
+
+    pub fn build(&self) -> Result<Types, Error> {
+        let mut include = GlobSetBuilder::new();
+        let mut exclude = GlobSetBuilder::new();
+
+        for name in &self.negated {
+            for glob in try!(self.globs_for(name)) {
+                exclude.add(try!(Glob::new(glob)));
+            }
+        }
+
+        for name in &self.selected {
+            for glob in try!(self.globs_for(name)) {
+                include.add(try!(Glob::new(glob)));
+            }
+        }
+
+        Ok(Types {
+            include: try!(include.build()),
+            exclude: try!(exclude.build()),
+        })
+    }
+
+    fn globs_for(&self, name: &str) -> Result<&Vec<String>, Error> {
+        self.definitions
+            .get(name)
+            .ok_or_else(|| Error::UnknownType(name.to_string()))
+    }
+}
+
+/// A compiled file-type selection, ready to test paths against.
+pub struct Types {
+    include: GlobSet,
+    exclude: GlobSet,
+}
+
+impl Types {
+    pub fn matched(&self, path: &Path) -> Match {
+        if self.exclude.is_match(path) {
+            return Match::Ignored;
+        }
+
+        if self.include.len() > 0 && self.include.is_match(path) {
+            return Match::Included;
+        }
+
+        Match::Unmatched
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::TypesBuilder;
+    use super::Match;
+    use std::path::Path;
+
+    #[test]
+    fn test_selects_default_type() {
+        let types = TypesBuilder::new().select("rust").build().unwrap();
+
+        assert_eq!(types.matched(Path::new("main.rs")), Match::Included);
+        assert_eq!(types.matched(Path::new("main.c")), Match::Unmatched);
+    }
+
+    #[test]
+    fn test_negate_overrides_select() {
+        let mut builder = TypesBuilder::new();
+        builder.select("rust");
+        builder.negate("rust");
+        let types = builder.build().unwrap();
+
+        assert_eq!(types.matched(Path::new("main.rs")), Match::Ignored);
+    }
+
+    #[test]
+    fn test_user_defined_type() {
+        let mut builder = TypesBuilder::new();
+        builder.add_def("proto", vec!["*.proto"]);
+        builder.select("proto");
+        let types = builder.build().unwrap();
+
+        assert_eq!(types.matched(Path::new("service.proto")), Match::Included);
+    }
+
+    #[test]
+    fn test_unknown_type_is_error() {
+        let mut builder = TypesBuilder::new();
+        builder.select("nonexistent");
+
+        assert!(builder.build().is_err());
+    }
+
+    #[test]
+    fn test_no_selection_is_unmatched() {
+        let types = TypesBuilder::new().build().unwrap();
+
+        assert_eq!(types.matched(Path::new("main.rs")), Match::Unmatched);
+    }
+}